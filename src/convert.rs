@@ -0,0 +1,126 @@
+//! Resize and re-encode an extracted preview.
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::io::Cursor;
+
+/// Output image formats we can re-encode an extracted preview to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        })
+    }
+}
+
+impl OutputFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Jpeg => ImageFormat::Jpeg,
+            Self::Png => ImageFormat::Png,
+            Self::WebP => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Resize `img` so its longest edge is at most `max_edge` pixels, preserving aspect
+/// ratio. Images already within bounds are returned unchanged.
+fn resize_longest_edge(img: DynamicImage, max_edge: u32) -> DynamicImage {
+    if img.width().max(img.height()) <= max_edge {
+        return img;
+    }
+
+    if img.width() >= img.height() {
+        img.resize(max_edge, u32::MAX, FilterType::Lanczos3)
+    } else {
+        img.resize(u32::MAX, max_edge, FilterType::Lanczos3)
+    }
+}
+
+/// Decode `jpeg`, optionally resize it to `max_edge`'s longest edge, and re-encode it
+/// as `format` at `quality` (1-100). `quality` only affects JPEG output: `image`'s
+/// WebP encoder is lossless-only (no libwebp bindings for lossy encoding), and PNG
+/// is always lossless.
+pub fn convert(jpeg: &[u8], max_edge: Option<u32>, format: OutputFormat, quality: u8) -> Result<Vec<u8>> {
+    let mut img = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg)?;
+
+    if let Some(max_edge) = max_edge {
+        img = resize_longest_edge(img, max_edge);
+    }
+
+    let mut out = Cursor::new(Vec::new());
+    match format {
+        OutputFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality);
+            img.write_with_encoder(encoder)?;
+        }
+        OutputFormat::Png | OutputFormat::WebP => {
+            img.write_to(&mut out, format.image_format())?;
+        }
+    }
+
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    /// Encode a solid-color `width`x`height` image as JPEG bytes, the same shape of
+    /// input `convert` expects.
+    fn sample_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = ImageBuffer::from_pixel(width, height, Rgb([200u8, 100, 50]));
+        let mut out = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(img).write_to(&mut out, ImageFormat::Jpeg).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn leaves_images_within_bounds_unresized() {
+        let jpeg = sample_jpeg(20, 10);
+        let out = convert(&jpeg, Some(100), OutputFormat::Jpeg, 90).unwrap();
+        let decoded = image::load_from_memory_with_format(&out, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (20, 10));
+    }
+
+    #[test]
+    fn resizes_to_the_longest_edge_preserving_aspect_ratio() {
+        let jpeg = sample_jpeg(40, 20);
+        let out = convert(&jpeg, Some(20), OutputFormat::Jpeg, 90).unwrap();
+        let decoded = image::load_from_memory_with_format(&out, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (20, 10));
+    }
+
+    #[test]
+    fn converts_to_png() {
+        let jpeg = sample_jpeg(8, 8);
+        let out = convert(&jpeg, None, OutputFormat::Png, 90).unwrap();
+        assert_eq!(image::guess_format(&out).unwrap(), ImageFormat::Png);
+    }
+
+    #[test]
+    fn converts_to_webp() {
+        let jpeg = sample_jpeg(8, 8);
+        let out = convert(&jpeg, None, OutputFormat::WebP, 90).unwrap();
+        assert_eq!(image::guess_format(&out).unwrap(), ImageFormat::WebP);
+    }
+}
@@ -0,0 +1,556 @@
+//! Minimal TIFF/EXIF reading, just enough to resolve and apply preview orientation.
+
+use anyhow::{anyhow, bail, Result};
+use image::ImageFormat;
+use std::io::Cursor;
+
+const TAG_IMAGE_WIDTH: u16 = 0x0100;
+const TAG_IMAGE_LENGTH: u16 = 0x0101;
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_JPEG_IF_OFFSET: u16 = 0x0201;
+const TAG_JPEG_IF_BYTE_COUNT: u16 = 0x0202;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_MAKERNOTE: u16 = 0x927C;
+const TAG_DATETIME_ORIGINAL: u16 = 0x9003;
+const TAG_CFA_REPEAT_PATTERN_DIM: u16 = 0x828D;
+const TAG_CFA_PATTERN: u16 = 0x828E;
+
+/// `CFAPattern` colour codes (0=Red, 1=Green, 2=Blue) for a standard 2x2 RGGB Bayer
+/// layout, row-major.
+const RGGB_PATTERN: [u8; 4] = [0, 1, 1, 2];
+
+/// Sony Makernote camera-orientation tag, relative to the Makernote's own IFD.
+const SONY_TAG_CAMERA_ORIENTATION: u16 = 0x0028;
+
+/// The eight EXIF orientation values (tag 0x0112), as the spec defines them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Normal = 1,
+    FlipHorizontal = 2,
+    Rotate180 = 3,
+    FlipVertical = 4,
+    Transpose = 5,
+    Rotate90 = 6,
+    Transverse = 7,
+    Rotate270 = 8,
+}
+
+impl Orientation {
+    fn from_tag_value(value: u16) -> Option<Self> {
+        Some(match value {
+            1 => Self::Normal,
+            2 => Self::FlipHorizontal,
+            3 => Self::Rotate180,
+            4 => Self::FlipVertical,
+            5 => Self::Transpose,
+            6 => Self::Rotate90,
+            7 => Self::Transverse,
+            8 => Self::Rotate270,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+struct TiffReader<'a> {
+    data: &'a [u8],
+    endian: Endian,
+}
+
+impl<'a> TiffReader<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        let endian = match data.get(0..2) {
+            Some(b"II") => Endian::Little,
+            Some(b"MM") => Endian::Big,
+            _ => bail!("not a TIFF/EXIF byte stream"),
+        };
+        Ok(Self { data, endian })
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16> {
+        let b = self
+            .data
+            .get(offset..offset + 2)
+            .ok_or_else(|| anyhow!("truncated TIFF data reading u16 at {offset}"))?;
+        Ok(match self.endian {
+            Endian::Little => u16::from_le_bytes([b[0], b[1]]),
+            Endian::Big => u16::from_be_bytes([b[0], b[1]]),
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32> {
+        let b = self
+            .data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("truncated TIFF data reading u32 at {offset}"))?;
+        Ok(match self.endian {
+            Endian::Little => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            Endian::Big => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        })
+    }
+
+    /// Returns `(tag, type, count, value_offset)` for each entry in the IFD at `offset`.
+    fn read_ifd(&self, offset: usize) -> Result<Vec<(u16, u16, u32, usize)>> {
+        let count = self.u16_at(offset)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let entry_offset = offset + 2 + i as usize * 12;
+            let tag = self.u16_at(entry_offset)?;
+            let ty = self.u16_at(entry_offset + 2)?;
+            let value_count = self.u32_at(entry_offset + 4)?;
+            entries.push((tag, ty, value_count, entry_offset + 8));
+        }
+        Ok(entries)
+    }
+
+    fn short_value(&self, ty: u16, value_offset: usize) -> Result<u16> {
+        // SHORT (3) values up to 2 bytes are stored inline at value_offset; anything
+        // else would need to follow an indirection we don't need for orientation tags.
+        if ty != 3 {
+            bail!("expected SHORT tag, got type {ty}");
+        }
+        self.u16_at(value_offset)
+    }
+
+    /// Read a LONG (type 4) or SHORT (type 3) tag's value(s) as `u32`s, following
+    /// the offset indirection when the values don't fit inline.
+    fn u32_array(&self, ty: u16, count: u32, value_field_offset: usize) -> Result<Vec<u32>> {
+        let elem_size: usize = match ty {
+            3 => 2,
+            4 => 4,
+            _ => bail!("expected SHORT or LONG tag, got type {ty}"),
+        };
+        let total_size = elem_size * count as usize;
+        let start = if total_size <= 4 {
+            value_field_offset
+        } else {
+            self.u32_at(value_field_offset)? as usize
+        };
+
+        (0..count as usize)
+            .map(|i| {
+                let offset = start + i * elem_size;
+                if ty == 3 {
+                    self.u16_at(offset).map(u32::from)
+                } else {
+                    self.u32_at(offset)
+                }
+            })
+            .collect()
+    }
+
+    /// Read a BYTE (type 1) array tag's raw bytes, following the offset indirection
+    /// when the values don't fit inline.
+    fn byte_array(&self, ty: u16, count: u32, value_field_offset: usize) -> Result<&'a [u8]> {
+        if ty != 1 {
+            bail!("expected BYTE tag, got type {ty}");
+        }
+        let len = count as usize;
+        let start = if len <= 4 {
+            value_field_offset
+        } else {
+            self.u32_at(value_field_offset)? as usize
+        };
+        self.data
+            .get(start..start + len)
+            .ok_or_else(|| anyhow!("truncated TIFF data reading BYTE array at {start}"))
+    }
+
+    fn ascii_value(&self, ty: u16, count: u32, value_field_offset: usize) -> Result<String> {
+        if ty != 2 {
+            bail!("expected ASCII tag, got type {ty}");
+        }
+        let len = count as usize;
+        // Values up to 4 bytes are stored inline in the value field; longer ones are
+        // stored elsewhere, with the value field holding the offset to them.
+        let start = if len <= 4 {
+            value_field_offset
+        } else {
+            self.u32_at(value_field_offset)? as usize
+        };
+        let bytes = self
+            .data
+            .get(start..start + len)
+            .ok_or_else(|| anyhow!("truncated TIFF data reading ASCII at {start}"))?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
+
+/// Whether `data` (a TIFF-preamble byte stream — not necessarily a whole file) has
+/// non-empty `Make` and `Model` tags in IFD0. [`crate::magic::classify`] uses this
+/// to tell a RAW file's TIFF container apart from an ordinary scanned/screenshot
+/// TIFF, which shares the same `II*\0`/`MM\0*` preamble but typically carries
+/// neither tag.
+pub(crate) fn ifd0_has_make_and_model(data: &[u8]) -> bool {
+    let Ok(reader) = TiffReader::new(data) else {
+        return false;
+    };
+    let Ok(ifd0_offset) = reader.u32_at(4) else {
+        return false;
+    };
+    let Ok(ifd0) = reader.read_ifd(ifd0_offset as usize) else {
+        return false;
+    };
+
+    let make = find_tag(&ifd0, TAG_MAKE).and_then(|(ty, count, off)| reader.ascii_value(ty, count, off).ok());
+    let model = find_tag(&ifd0, TAG_MODEL).and_then(|(ty, count, off)| reader.ascii_value(ty, count, off).ok());
+
+    matches!(make, Some(m) if !m.trim().is_empty()) && matches!(model, Some(m) if !m.trim().is_empty())
+}
+
+fn find_tag(entries: &[(u16, u16, u32, usize)], tag: u16) -> Option<(u16, u32, usize)> {
+    entries
+        .iter()
+        .find(|(t, ..)| *t == tag)
+        .map(|(_, ty, count, off)| (*ty, *count, *off))
+}
+
+/// Read the Sony Makernote's camera-orientation tag, if present.
+fn sony_makernote_orientation(reader: &TiffReader, makernote_offset: usize) -> Option<Orientation> {
+    let entries = reader.read_ifd(makernote_offset).ok()?;
+    let (ty, _, value_offset) = find_tag(&entries, SONY_TAG_CAMERA_ORIENTATION)?;
+    let value = reader.short_value(ty, value_offset).ok()?;
+    Orientation::from_tag_value(value)
+}
+
+/// Resolve the orientation that should be applied to a preview, preferring a
+/// Makernote camera-orientation value over the top-level EXIF tag when both exist
+/// and disagree. Returns `Ok(None)` when there's no orientation info at all.
+pub fn resolve_orientation(raw_data: &[u8]) -> Result<Option<Orientation>> {
+    let reader = TiffReader::new(raw_data)?;
+    let ifd0_offset = reader.u32_at(4)? as usize;
+    let ifd0 = reader.read_ifd(ifd0_offset)?;
+
+    let top_level = find_tag(&ifd0, TAG_ORIENTATION)
+        .and_then(|(ty, _, off)| reader.short_value(ty, off).ok())
+        .and_then(Orientation::from_tag_value);
+
+    let exif_ifd_offset = find_tag(&ifd0, TAG_EXIF_IFD).map(|(_, _, off)| off);
+    let makernote = exif_ifd_offset
+        .and_then(|off| reader.u32_at(off).ok())
+        .and_then(|exif_ifd_offset| reader.read_ifd(exif_ifd_offset as usize).ok())
+        .and_then(|exif_entries| find_tag(&exif_entries, TAG_MAKERNOTE))
+        .and_then(|(_, _, off)| reader.u32_at(off).ok())
+        .and_then(|makernote_offset| sony_makernote_orientation(&reader, makernote_offset as usize));
+
+    Ok(makernote.or(top_level))
+}
+
+/// Metadata pulled from a preview JPEG's own EXIF block, used to organise output.
+#[derive(Debug, Clone, Default)]
+pub struct PreviewMetadata {
+    pub datetime_original: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Locate the `Exif\0\0`-prefixed TIFF payload inside a JPEG's APP1 segment, if any.
+fn find_exif_tiff_in_jpeg(jpeg: &[u8]) -> Option<&[u8]> {
+    const APP1: u8 = 0xE1;
+    const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+    let mut pos = 2; // skip SOI
+    while pos + 4 <= jpeg.len() {
+        if jpeg[pos] != 0xFF {
+            break;
+        }
+        let marker = jpeg[pos + 1];
+        if marker == 0xD8 || marker == 0xD9 {
+            pos += 2;
+            continue;
+        }
+        let segment_len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        let payload_start = pos + 4;
+        let payload_end = payload_start + segment_len.saturating_sub(2);
+
+        if marker == APP1 {
+            let payload = jpeg.get(payload_start..payload_end)?;
+            if let Some(tiff) = payload.strip_prefix(EXIF_HEADER) {
+                return Some(tiff);
+            }
+        }
+
+        if marker == 0xDA {
+            // Start of scan: no more markers to look at before compressed data.
+            break;
+        }
+        pos = payload_end;
+    }
+    None
+}
+
+/// Read `DateTimeOriginal`/`Make`/`Model` out of a preview JPEG's embedded EXIF.
+pub fn extract_preview_metadata(jpeg: &[u8]) -> Result<PreviewMetadata> {
+    let Some(tiff) = find_exif_tiff_in_jpeg(jpeg) else {
+        return Ok(PreviewMetadata::default());
+    };
+
+    let reader = TiffReader::new(tiff)?;
+    let ifd0_offset = reader.u32_at(4)? as usize;
+    let ifd0 = reader.read_ifd(ifd0_offset)?;
+
+    let make = find_tag(&ifd0, TAG_MAKE).and_then(|(ty, count, off)| reader.ascii_value(ty, count, off).ok());
+    let model = find_tag(&ifd0, TAG_MODEL).and_then(|(ty, count, off)| reader.ascii_value(ty, count, off).ok());
+
+    let exif_ifd = find_tag(&ifd0, TAG_EXIF_IFD)
+        .and_then(|(_, _, off)| reader.u32_at(off).ok())
+        .and_then(|off| reader.read_ifd(off as usize).ok());
+    let datetime_original = exif_ifd
+        .as_deref()
+        .and_then(|entries| find_tag(entries, TAG_DATETIME_ORIGINAL))
+        .and_then(|(ty, count, off)| reader.ascii_value(ty, count, off).ok());
+
+    Ok(PreviewMetadata {
+        datetime_original,
+        make,
+        model,
+    })
+}
+
+/// IFD0, plus any SubIFDs (tag 0x014A) it points to — the set of IFDs worth
+/// checking for per-image tags like strip or JPEG-preview pointers.
+fn collect_ifd_offsets(reader: &TiffReader) -> Result<Vec<usize>> {
+    let ifd0_offset = reader.u32_at(4)? as usize;
+    let ifd0 = reader.read_ifd(ifd0_offset)?;
+
+    let mut offsets = vec![ifd0_offset];
+    if let Some((ty, count, off)) = find_tag(&ifd0, TAG_SUB_IFDS) {
+        offsets.extend(reader.u32_array(ty, count, off)?.into_iter().map(|o| o as usize));
+    }
+    Ok(offsets)
+}
+
+/// Find the byte range of every `JpegIFOffset`/`JpegIFByteCount` tag pair across
+/// IFD0 and its SubIFDs — the IFD-side pointers to embedded JPEG previews, used to
+/// cross-check spans found by scanning for SOI/EOI markers.
+pub fn jpeg_ifd_spans(raw_data: &[u8]) -> Result<Vec<(usize, usize)>> {
+    let reader = TiffReader::new(raw_data)?;
+    let mut spans = Vec::new();
+
+    for ifd_offset in collect_ifd_offsets(&reader)? {
+        let Ok(entries) = reader.read_ifd(ifd_offset) else {
+            continue;
+        };
+        let Some((off_ty, off_count, off_off)) = find_tag(&entries, TAG_JPEG_IF_OFFSET) else {
+            continue;
+        };
+        let Some((len_ty, len_count, len_off)) = find_tag(&entries, TAG_JPEG_IF_BYTE_COUNT) else {
+            continue;
+        };
+        let (Ok(offsets), Ok(lens)) = (
+            reader.u32_array(off_ty, off_count, off_off),
+            reader.u32_array(len_ty, len_count, len_off),
+        ) else {
+            continue;
+        };
+        spans.extend(
+            offsets
+                .into_iter()
+                .zip(lens)
+                .map(|(o, l)| (o as usize, l as usize)),
+        );
+    }
+
+    Ok(spans)
+}
+
+/// Find the byte range of the highest-resolution single-strip image data in a RAW
+/// file's TIFF structure, by walking IFD0 and any SubIFDs it points to. Only IFDs
+/// with a single strip (`StripOffsets`/`StripByteCounts` each with count 1) are
+/// considered, since that's the layout [`crate::raw_decode`] can decode.
+pub fn find_largest_strip(raw_data: &[u8]) -> Result<Option<(usize, usize)>> {
+    let reader = TiffReader::new(raw_data)?;
+    let candidate_offsets = collect_ifd_offsets(&reader)?;
+
+    let mut best: Option<(u64, usize, usize)> = None; // (pixel_count, offset, len)
+
+    for ifd_offset in candidate_offsets {
+        let Ok(entries) = reader.read_ifd(ifd_offset) else {
+            continue;
+        };
+
+        let Some((w_ty, w_count, w_off)) = find_tag(&entries, TAG_IMAGE_WIDTH) else {
+            continue;
+        };
+        let Some((h_ty, h_count, h_off)) = find_tag(&entries, TAG_IMAGE_LENGTH) else {
+            continue;
+        };
+        let Some((so_ty, so_count, so_off)) = find_tag(&entries, TAG_STRIP_OFFSETS) else {
+            continue;
+        };
+        let Some((sc_ty, sc_count, sc_off)) = find_tag(&entries, TAG_STRIP_BYTE_COUNTS) else {
+            continue;
+        };
+        if so_count != 1 || sc_count != 1 {
+            continue; // multi-strip layouts aren't supported by the decode fallback.
+        }
+
+        let Ok(width) = reader.u32_array(w_ty, w_count, w_off) else {
+            continue;
+        };
+        let Ok(height) = reader.u32_array(h_ty, h_count, h_off) else {
+            continue;
+        };
+        let Ok(strip_offset) = reader.u32_array(so_ty, so_count, so_off) else {
+            continue;
+        };
+        let Ok(strip_len) = reader.u32_array(sc_ty, sc_count, sc_off) else {
+            continue;
+        };
+
+        let pixels = width[0] as u64 * height[0] as u64;
+        if best.map_or(true, |(best_pixels, ..)| pixels > best_pixels) {
+            best = Some((pixels, strip_offset[0] as usize, strip_len[0] as usize));
+        }
+    }
+
+    Ok(best.map(|(_, offset, len)| (offset, len)))
+}
+
+/// Whether the sensor strip's `CFARepeatPatternDim`/`CFAPattern` tags (checked
+/// across IFD0 and any SubIFDs, same as [`find_largest_strip`]) describe a standard
+/// 2x2 RGGB Bayer layout. [`crate::raw_decode`]'s demosaic only implements RGGB, so
+/// a different phase (GRBG/BGGR/GBRG) must be rejected rather than silently
+/// demosaiced as if it were RGGB. Absent tags default to RGGB, matching the common
+/// case this module targets.
+pub fn is_rggb_cfa(raw_data: &[u8]) -> Result<bool> {
+    let reader = TiffReader::new(raw_data)?;
+
+    for ifd_offset in collect_ifd_offsets(&reader)? {
+        let Ok(entries) = reader.read_ifd(ifd_offset) else {
+            continue;
+        };
+        let Some((dim_ty, dim_count, dim_off)) = find_tag(&entries, TAG_CFA_REPEAT_PATTERN_DIM) else {
+            continue;
+        };
+        let Some((pat_ty, pat_count, pat_off)) = find_tag(&entries, TAG_CFA_PATTERN) else {
+            continue;
+        };
+        let Ok(dims) = reader.u32_array(dim_ty, dim_count, dim_off) else {
+            continue;
+        };
+        let Ok(pattern) = reader.byte_array(pat_ty, pat_count, pat_off) else {
+            continue;
+        };
+        return Ok(dims == [2u32, 2u32] && pattern == &RGGB_PATTERN[..]);
+    }
+
+    Ok(true)
+}
+
+/// Rotate/flip a JPEG so it displays upright for the given orientation.
+/// `Orientation::Normal` is a no-op and is returned unchanged rather than being
+/// decoded and re-encoded for nothing; any real transform is re-encoded at `quality`,
+/// the same as [`crate::convert::convert`]. The re-encoded output has no EXIF block
+/// (the source APP1 segment is not copied), so it carries no orientation tag; the
+/// tag is not rewritten to 1.
+pub fn apply_orientation(jpeg: &[u8], orientation: Orientation, quality: u8) -> Result<Vec<u8>> {
+    if orientation == Orientation::Normal {
+        return Ok(jpeg.to_vec());
+    }
+
+    let img = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg)?;
+
+    let img = match orientation {
+        Orientation::Normal => img,
+        Orientation::FlipHorizontal => img.fliph(),
+        Orientation::Rotate180 => img.rotate180(),
+        Orientation::FlipVertical => img.flipv(),
+        Orientation::Transpose => img.rotate90().fliph(),
+        Orientation::Rotate90 => img.rotate90(),
+        Orientation::Transverse => img.rotate270().fliph(),
+        Orientation::Rotate270 => img.rotate270(),
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality);
+    img.write_with_encoder(encoder)?;
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Build a minimal little-endian TIFF byte stream with IFD0's `Orientation` tag
+    /// set to `ifd0_orientation`, and (when `makernote_orientation` is `Some`) an
+    /// `ExifIFD` -> `Makernote` -> Sony `CameraOrientation` chain set to that value.
+    fn build_tiff(ifd0_orientation: u16, makernote_orientation: Option<u16>) -> Vec<u8> {
+        let mut buf = vec![0u8; 128];
+        buf[0..2].copy_from_slice(b"II");
+        put_u16(&mut buf, 2, 42);
+        put_u32(&mut buf, 4, 8);
+
+        // IFD0 @ 8: two entries, Orientation and ExifIFD.
+        put_u16(&mut buf, 8, 2);
+        put_u16(&mut buf, 10, TAG_ORIENTATION);
+        put_u16(&mut buf, 12, 3); // SHORT
+        put_u32(&mut buf, 14, 1);
+        put_u16(&mut buf, 18, ifd0_orientation);
+        put_u16(&mut buf, 22, TAG_EXIF_IFD);
+        put_u16(&mut buf, 24, 4); // LONG
+        put_u32(&mut buf, 26, 1);
+        put_u32(&mut buf, 30, 38); // Exif sub-IFD offset
+        put_u32(&mut buf, 34, 0); // next IFD
+
+        // Exif sub-IFD @ 38: one entry, Makernote.
+        put_u16(&mut buf, 38, 1);
+        put_u16(&mut buf, 40, TAG_MAKERNOTE);
+        put_u16(&mut buf, 42, 4);
+        put_u32(&mut buf, 44, 1);
+        put_u32(&mut buf, 48, 64); // Makernote IFD offset
+        put_u32(&mut buf, 52, 0);
+
+        // Makernote IFD @ 64, left as a zero-entry IFD unless a value is given.
+        if let Some(orientation) = makernote_orientation {
+            put_u16(&mut buf, 64, 1);
+            put_u16(&mut buf, 66, SONY_TAG_CAMERA_ORIENTATION);
+            put_u16(&mut buf, 68, 3);
+            put_u32(&mut buf, 70, 1);
+            put_u16(&mut buf, 74, orientation);
+            put_u32(&mut buf, 78, 0);
+        }
+
+        buf
+    }
+
+    #[test]
+    fn resolve_orientation_prefers_makernote_over_top_level() {
+        let tiff = build_tiff(3, Some(6)); // IFD0: Rotate180, Makernote: Rotate90
+        assert_eq!(resolve_orientation(&tiff).unwrap(), Some(Orientation::Rotate90));
+    }
+
+    #[test]
+    fn resolve_orientation_falls_back_to_top_level_without_makernote() {
+        let tiff = build_tiff(3, None);
+        assert_eq!(resolve_orientation(&tiff).unwrap(), Some(Orientation::Rotate180));
+    }
+
+    #[test]
+    fn apply_orientation_is_a_no_op_for_normal() {
+        // Deliberately not a real JPEG: Normal must short-circuit before any decode
+        // is attempted.
+        let fake_jpeg = b"not a real jpeg".to_vec();
+        let out = apply_orientation(&fake_jpeg, Orientation::Normal, 90).unwrap();
+        assert_eq!(out, fake_jpeg);
+    }
+}
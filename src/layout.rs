@@ -0,0 +1,138 @@
+//! Resolve `{year}/{month}/{camera_model}`-style output layouts from preview metadata.
+
+use crate::exif::PreviewMetadata;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// A `/`-separated pattern of `{year}`, `{month}`, `{day}`, `{make}` and
+/// `{camera_model}` placeholders, resolved per-file into an output subdirectory.
+#[derive(Debug, Clone)]
+pub struct LayoutTemplate {
+    pattern: String,
+}
+
+impl LayoutTemplate {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+        }
+    }
+
+    /// Resolve this template into a path relative to the output directory, using
+    /// `metadata`'s `DateTimeOriginal`/`Make`/`Model` where available and falling
+    /// back to `mtime` for the date when `DateTimeOriginal` is absent or unparsable.
+    pub fn resolve(&self, metadata: &PreviewMetadata, mtime: SystemTime) -> PathBuf {
+        let (year, month, day) = metadata
+            .datetime_original
+            .as_deref()
+            .and_then(parse_exif_date)
+            .unwrap_or_else(|| civil_from_systemtime(mtime));
+
+        let model = metadata.model.as_deref().unwrap_or("unknown_camera");
+        let make = metadata.make.as_deref().unwrap_or("unknown_make");
+
+        let resolved = self
+            .pattern
+            .replace("{year}", &format!("{year:04}"))
+            .replace("{month}", &format!("{month:02}"))
+            .replace("{day}", &format!("{day:02}"))
+            .replace("{make}", &sanitize(make))
+            .replace("{camera_model}", &sanitize(model));
+
+        PathBuf::from(resolved)
+    }
+}
+
+/// Component names aren't allowed to contain path separators, be empty, or be a
+/// `.`/`..` traversal component — `Make`/`Model` come straight from the preview's
+/// own (attacker-controlled) EXIF, not the RAW filename, so a value of exactly
+/// `".."` must not be allowed to escape the output directory.
+fn sanitize(component: &str) -> String {
+    let cleaned: String = component
+        .chars()
+        .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+        .collect();
+    if cleaned.trim().is_empty() || cleaned == "." || cleaned == ".." {
+        "unknown".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Parse an EXIF datetime string (`"YYYY:MM:DD HH:MM:SS"`) into `(year, month, day)`.
+fn parse_exif_date(s: &str) -> Option<(i32, u32, u32)> {
+    let date_part = s.split(' ').next()?;
+    let mut fields = date_part.split(':');
+    let year = fields.next()?.parse().ok()?;
+    let month = fields.next()?.parse().ok()?;
+    let day = fields.next()?.parse().ok()?;
+    Some((year, month, day))
+}
+
+/// Convert a [`SystemTime`] to a civil `(year, month, day)` in UTC, using Howard
+/// Hinnant's `civil_from_days` algorithm so we don't need a date/time dependency
+/// just for this fallback.
+fn civil_from_systemtime(time: SystemTime) -> (i32, u32, u32) {
+    let days = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = (z - era * 146097) as u64; // [0, 146096]
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365; // [0, 399]
+    let y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100); // [0, 365]
+    let mp = (5 * doy + 2) / 153; // [0, 11]
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32; // [1, 31]
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32; // [1, 12]
+    let y = if m <= 2 { y + 1 } else { y };
+
+    (y as i32, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(make: Option<&str>, model: Option<&str>, datetime: Option<&str>) -> PreviewMetadata {
+        PreviewMetadata {
+            datetime_original: datetime.map(str::to_string),
+            make: make.map(str::to_string),
+            model: model.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn resolves_placeholders_from_metadata() {
+        let template = LayoutTemplate::new("{year}/{month}/{day}/{make}/{camera_model}");
+        let metadata = metadata(Some("Canon"), Some("EOS R5"), Some("2024:03:09 12:00:00"));
+        let resolved = template.resolve(&metadata, SystemTime::UNIX_EPOCH);
+        assert_eq!(resolved, PathBuf::from("2024/03/09/Canon/EOS R5"));
+    }
+
+    #[test]
+    fn falls_back_to_mtime_and_unknowns_when_metadata_is_absent() {
+        let template = LayoutTemplate::new("{year}/{make}/{camera_model}");
+        let metadata = metadata(None, None, None);
+        let resolved = template.resolve(&metadata, SystemTime::UNIX_EPOCH);
+        assert_eq!(resolved, PathBuf::from("1970/unknown_make/unknown_camera"));
+    }
+
+    #[test]
+    fn sanitize_rejects_dot_and_dotdot_components() {
+        let template = LayoutTemplate::new("{make}/{camera_model}");
+        let metadata = metadata(Some(".."), Some("."), None);
+        let resolved = template.resolve(&metadata, SystemTime::UNIX_EPOCH);
+        assert_eq!(resolved, PathBuf::from("unknown/unknown"));
+    }
+
+    #[test]
+    fn sanitize_replaces_path_separators() {
+        let template = LayoutTemplate::new("{make}");
+        let metadata = metadata(Some("Weird/Make\\Name"), None, None);
+        let resolved = template.resolve(&metadata, SystemTime::UNIX_EPOCH);
+        assert_eq!(resolved, PathBuf::from("Weird_Make_Name"));
+    }
+}
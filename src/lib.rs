@@ -0,0 +1,361 @@
+//! Extract embedded JPEG previews from camera RAW files.
+
+mod convert;
+mod exif;
+mod layout;
+mod ljpeg;
+mod magic;
+mod preview;
+mod raw_decode;
+
+pub use convert::OutputFormat;
+pub use exif::Orientation;
+pub use layout::LayoutTemplate;
+pub use preview::PreviewSelection;
+
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// RAW file extensions we look for by default.
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "arw", "cr2", "crw", "dng", "erf", "kdc", "mef", "mrw", "nef", "nrw", "orf", "pef", "raf",
+    "raw", "rw2", "rwl", "sr2", "srf", "srw", "x3f",
+];
+
+/// Configuration for an [`extract_directory`] run.
+#[derive(Clone)]
+pub struct ExtractionConfig {
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    transfers: usize,
+    extra_extension: Option<OsString>,
+    auto_orient: bool,
+    magic_detection: bool,
+    resize: Option<u32>,
+    format: OutputFormat,
+    quality: u8,
+    layout: Option<LayoutTemplate>,
+    decode_fallback: bool,
+    preview_selection: PreviewSelection,
+}
+
+impl ExtractionConfig {
+    /// Create a new config that extracts from `input_dir` into `output_dir`.
+    pub fn new(input_dir: PathBuf, output_dir: PathBuf) -> Self {
+        Self {
+            input_dir,
+            output_dir,
+            transfers: 8,
+            extra_extension: None,
+            auto_orient: false,
+            magic_detection: false,
+            resize: None,
+            format: OutputFormat::Jpeg,
+            quality: 90,
+            layout: None,
+            decode_fallback: false,
+            preview_selection: PreviewSelection::Largest,
+        }
+    }
+
+    /// How many files to process concurrently.
+    pub fn with_transfers(mut self, transfers: usize) -> Self {
+        self.transfers = transfers;
+        self
+    }
+
+    /// Look for this extension in addition to the default list.
+    pub fn with_extension(mut self, extension: Option<OsString>) -> Self {
+        self.extra_extension = extension;
+        self
+    }
+
+    /// Rotate/flip extracted previews upright according to EXIF/Makernote orientation.
+    /// A rotated or flipped preview is re-encoded without its EXIF block, so the
+    /// output file carries no `Orientation` tag; the tag is not rewritten to 1.
+    ///
+    /// When the top-level EXIF `Orientation` tag (0x0112) and a body's Makernote
+    /// camera-orientation value disagree, the Makernote value wins. A warning is
+    /// emitted when orientation can't be resolved instead of guessing.
+    pub fn with_auto_orient(mut self, auto_orient: bool) -> Self {
+        self.auto_orient = auto_orient;
+        self
+    }
+
+    /// Identify RAW candidates by sniffing file contents instead of trusting the
+    /// file extension, so a directory of mixed or misnamed files still gets fully
+    /// processed. When enabled, this replaces the extension allow-list entirely.
+    pub fn with_magic_detection(mut self, magic_detection: bool) -> Self {
+        self.magic_detection = magic_detection;
+        self
+    }
+
+    /// Downscale extracted previews so their longest edge is at most `max_edge`
+    /// pixels, preserving aspect ratio. `None` (the default) keeps the embedded
+    /// preview at its original resolution.
+    pub fn with_resize(mut self, max_edge: Option<u32>) -> Self {
+        self.resize = max_edge;
+        self
+    }
+
+    /// Re-encode extracted previews to `format` instead of leaving them as JPEG.
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Quality (1-100) to use when re-encoding as JPEG. PNG/WebP output is always
+    /// encoded lossless; with auto-orient, a rotated preview is first re-encoded as
+    /// JPEG at this quality before any PNG/WebP conversion.
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Organise extracted previews into subdirectories of the output directory,
+    /// resolved per-file from the preview's EXIF `DateTimeOriginal` and `Make`/`Model`
+    /// tags (falling back to the input file's mtime when `DateTimeOriginal` is
+    /// absent). `None` (the default) writes every preview flat into the output dir.
+    pub fn with_layout(mut self, layout: Option<LayoutTemplate>) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// When a RAW file has no usable embedded JPEG preview, decode the sensor data
+    /// itself and render it as a JPEG instead of producing nothing. Off by default,
+    /// since demosaicing is far slower than extracting an existing preview.
+    pub fn with_decode_fallback(mut self, decode_fallback: bool) -> Self {
+        self.decode_fallback = decode_fallback;
+        self
+    }
+
+    /// Which embedded preview(s) to extract when a RAW file contains more than one,
+    /// e.g. a small thumbnail alongside a near-full-resolution preview. Defaults to
+    /// the largest, since that's almost always what users want.
+    pub fn with_preview_selection(mut self, preview_selection: PreviewSelection) -> Self {
+        self.preview_selection = preview_selection;
+        self
+    }
+
+    fn matches_extension(&self, path: &Path) -> bool {
+        let Some(ext) = path.extension() else {
+            return false;
+        };
+        let ext = ext.to_ascii_lowercase();
+        if DEFAULT_EXTENSIONS.iter().any(|e| ext == *e) {
+            return true;
+        }
+        if let Some(extra) = &self.extra_extension {
+            return ext.eq_ignore_ascii_case(extra);
+        }
+        false
+    }
+}
+
+/// Read just enough of `path` to run it through [`magic::classify`].
+async fn sniff_is_raw(path: &Path) -> Result<bool> {
+    let mut file = fs::File::open(path)
+        .await
+        .with_context(|| format!("opening {}", path.display()))?;
+    let mut buf = [0u8; magic::SNIFF_LEN];
+    let n = file.read(&mut buf).await?;
+    Ok(magic::is_raw_file(&buf[..n]))
+}
+
+/// Per-file settings threaded into each extraction task. Cheap to clone, so we hand
+/// each spawned task its own copy rather than sharing `&ExtractionConfig`.
+#[derive(Clone)]
+struct ExtractOptions {
+    auto_orient: bool,
+    resize: Option<u32>,
+    format: OutputFormat,
+    quality: u8,
+    layout: Option<LayoutTemplate>,
+    decode_fallback: bool,
+    preview_selection: PreviewSelection,
+}
+
+impl From<&ExtractionConfig> for ExtractOptions {
+    fn from(config: &ExtractionConfig) -> Self {
+        Self {
+            auto_orient: config.auto_orient,
+            resize: config.resize,
+            format: config.format,
+            quality: config.quality,
+            layout: config.layout.clone(),
+            decode_fallback: config.decode_fallback,
+            preview_selection: config.preview_selection,
+        }
+    }
+}
+
+/// Process one already-selected preview through orientation/resize/layout and
+/// write it to `output_dir`, with a `_<size>` suffix on the file stem when
+/// `size_suffix` is set (used for [`PreviewSelection::All`]).
+async fn write_preview(
+    mut jpeg: Vec<u8>,
+    raw_data: &[u8],
+    path: &Path,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    size_suffix: Option<usize>,
+) -> Result<()> {
+    // Extract layout metadata from the original preview bytes: orientation/resize/
+    // format conversion below all round-trip the JPEG through `image`, which drops
+    // the source APP1/EXIF segment, so reading it after either would always see
+    // nothing and silently fall back to "unknown".
+    let layout_metadata = options
+        .layout
+        .is_some()
+        .then(|| exif::extract_preview_metadata(&jpeg).unwrap_or_default());
+
+    if options.auto_orient {
+        match exif::resolve_orientation(raw_data) {
+            Ok(Some(orientation)) => {
+                jpeg = exif::apply_orientation(&jpeg, orientation, options.quality)
+                    .with_context(|| format!("applying orientation to {}", path.display()))?;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!(
+                    "warning: could not resolve orientation for {}: {e}",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    if options.resize.is_some() || options.format != OutputFormat::Jpeg {
+        jpeg = convert::convert(&jpeg, options.resize, options.format, options.quality)
+            .with_context(|| format!("converting {}", path.display()))?;
+    }
+
+    let file_stem = path
+        .file_stem()
+        .context("input path has no file name")?
+        .to_string_lossy();
+    let file_stem = match size_suffix {
+        Some(size) => format!("{file_stem}_{size}"),
+        None => file_stem.into_owned(),
+    };
+
+    let dest_dir = match &options.layout {
+        Some(layout) => {
+            let metadata = layout_metadata.unwrap_or_default();
+            let mtime = fs::metadata(&path).await?.modified()?;
+            let subdir = output_dir.join(layout.resolve(&metadata, mtime));
+            fs::create_dir_all(&subdir)
+                .await
+                .with_context(|| format!("creating {}", subdir.display()))?;
+            subdir
+        }
+        None => output_dir.to_path_buf(),
+    };
+
+    let mut out_path = dest_dir.join(file_stem);
+    out_path.set_extension(options.format.extension());
+
+    fs::write(&out_path, &jpeg)
+        .await
+        .with_context(|| format!("writing {}", out_path.display()))?;
+
+    Ok(())
+}
+
+async fn extract_one(path: PathBuf, output_dir: PathBuf, options: ExtractOptions) -> Result<()> {
+    let data = fs::read(&path)
+        .await
+        .with_context(|| format!("reading {}", path.display()))?;
+
+    let all_previews = preview::find_all_previews(&data);
+
+    if all_previews.is_empty() {
+        if !options.decode_fallback {
+            return Ok(());
+        }
+        let strip_location = match exif::find_largest_strip(&data) {
+            Ok(location) => location,
+            Err(e) => {
+                eprintln!("warning: could not locate a sensor strip in {}: {e}", path.display());
+                return Ok(());
+            }
+        };
+        let Some((strip_offset, strip_len)) = strip_location else {
+            eprintln!(
+                "warning: no embedded preview and no decodable sensor strip in {}",
+                path.display()
+            );
+            return Ok(());
+        };
+        let Some(strip) = data.get(strip_offset..strip_offset + strip_len) else {
+            eprintln!("warning: sensor strip in {} points outside the file", path.display());
+            return Ok(());
+        };
+        let jpeg = match raw_decode::decode_fallback(&data, strip) {
+            Ok(jpeg) => jpeg,
+            Err(e) => {
+                eprintln!("warning: could not decode sensor data in {}: {e}", path.display());
+                return Ok(());
+            }
+        };
+        return write_preview(jpeg, &data, &path, &output_dir, &options, None).await;
+    }
+
+    let selected = preview::select(&all_previews, options.preview_selection);
+    let emit_size_suffix = options.preview_selection == PreviewSelection::All;
+
+    for preview in selected {
+        let size_suffix = emit_size_suffix.then_some(preview.len());
+        write_preview(preview.to_vec(), &data, &path, &output_dir, &options, size_suffix).await?;
+    }
+
+    Ok(())
+}
+
+/// Extract the embedded JPEG preview from every RAW file in `config`'s input directory.
+pub async fn extract_directory(config: ExtractionConfig) -> Result<()> {
+    fs::create_dir_all(&config.output_dir)
+        .await
+        .with_context(|| format!("creating {}", config.output_dir.display()))?;
+
+    let mut entries = fs::read_dir(&config.input_dir)
+        .await
+        .with_context(|| format!("reading directory {}", config.input_dir.display()))?;
+
+    let semaphore = std::sync::Arc::new(Semaphore::new(config.transfers.max(1)));
+    let mut tasks = JoinSet::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+
+        if config.magic_detection {
+            if !sniff_is_raw(&path).await? {
+                continue;
+            }
+        } else if !config.matches_extension(&path) {
+            continue;
+        }
+
+        let output_dir = config.output_dir.clone();
+        let options = ExtractOptions::from(&config);
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            extract_one(path, output_dir, options).await
+        });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result.context("extraction task panicked")??;
+    }
+
+    Ok(())
+}
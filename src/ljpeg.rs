@@ -0,0 +1,425 @@
+//! Minimal lossless JPEG (ITU-T T.81 Annex H / "LJPEG") decoder.
+//!
+//! DNG, NEF and CR2 wrap their sensor data in LJPEG-compressed tiles or strips:
+//! a `SOF3` frame describes the component layout, `DHT` segments build the
+//! Huffman tables, and each sample is reconstructed as `pred + huffman_diff`, where
+//! the predictor picks among the left/above/above-left neighbours per the predictor
+//! selection value in the `SOS` header. This only handles a single frame/scan per call, which
+//! covers the common case of one LJPEG stream per strip/tile.
+
+use anyhow::{anyhow, bail, Result};
+
+/// A decoded LJPEG stream: `components` interleaved `precision`-bit samples per
+/// pixel, `width * height` pixels, row-major.
+pub struct LjpegImage {
+    pub width: u16,
+    pub height: u16,
+    pub components: usize,
+    pub precision: u8,
+    pub samples: Vec<u16>,
+}
+
+#[derive(Default, Clone)]
+struct HuffmanTable {
+    /// Every `(length, code, symbol)` triple of the table, built from the
+    /// standard JPEG `bits`/`huffval` arrays using canonical code assignment.
+    codes: Vec<(u8, u16, u8)>, // (length, code, symbol)
+}
+
+impl HuffmanTable {
+    fn build(bits: &[u8; 16], huffval: &[u8]) -> Self {
+        let mut codes = Vec::with_capacity(huffval.len());
+        let mut code: u16 = 0;
+        let mut k = 0;
+        for (len_idx, &count) in bits.iter().enumerate() {
+            let len = (len_idx + 1) as u8;
+            for _ in 0..count {
+                codes.push((len, code, huffval[k]));
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        Self { codes }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+    hit_marker: Option<u8>,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+            hit_marker: None,
+        }
+    }
+
+    /// Pull the next entropy-coded byte, unstuffing `FF 00` to a literal `FF` and
+    /// recording (without consuming past) any real marker for the caller to handle.
+    fn fill(&mut self) -> Result<()> {
+        while self.bit_count <= 24 {
+            if self.hit_marker.is_some() {
+                self.bit_buf |= 0 << (24 - self.bit_count);
+                self.bit_count += 8;
+                continue;
+            }
+            let Some(&byte) = self.data.get(self.pos) else {
+                self.bit_buf |= 0 << (24 - self.bit_count);
+                self.bit_count += 8;
+                continue;
+            };
+            if byte == 0xFF {
+                match self.data.get(self.pos + 1) {
+                    Some(0x00) => {
+                        self.pos += 2;
+                        self.bit_buf |= (0xFFu32) << (24 - self.bit_count);
+                        self.bit_count += 8;
+                    }
+                    Some(&marker) => {
+                        self.hit_marker = Some(marker);
+                        self.pos += 2;
+                        continue;
+                    }
+                    None => bail!("truncated LJPEG entropy stream"),
+                }
+            } else {
+                self.pos += 1;
+                self.bit_buf |= (byte as u32) << (24 - self.bit_count);
+                self.bit_count += 8;
+            }
+        }
+        Ok(())
+    }
+
+    fn next_bit(&mut self) -> Result<u32> {
+        self.fill()?;
+        let bit = (self.bit_buf >> 31) & 1;
+        self.bit_buf <<= 1;
+        self.bit_count -= 1;
+        Ok(bit)
+    }
+
+    fn receive(&mut self, n: u8) -> Result<u32> {
+        let mut v = 0u32;
+        for _ in 0..n {
+            v = (v << 1) | self.next_bit()?;
+        }
+        Ok(v)
+    }
+
+    fn decode_huffman(&mut self, table: &HuffmanTable) -> Result<u8> {
+        let mut code: u16 = 0;
+        for len in 1..=16u8 {
+            code = (code << 1) | self.next_bit()? as u16;
+            if let Some((_, _, symbol)) = table
+                .codes
+                .iter()
+                .find(|(l, c, _)| *l == len && *c == code)
+            {
+                return Ok(*symbol);
+            }
+        }
+        Err(anyhow!("no matching Huffman code in LJPEG stream"))
+    }
+
+    /// Skip forward to just past a restart marker, if we're sitting on one.
+    fn skip_restart_marker(&mut self) {
+        if matches!(self.hit_marker, Some(m) if (0xD0..=0xD7).contains(&m)) {
+            self.hit_marker = None;
+            self.bit_buf = 0;
+            self.bit_count = 0;
+        }
+    }
+}
+
+/// Standard JPEG "extend": turn an `n`-bit magnitude-and-sign-coded value into a
+/// signed difference.
+fn extend(v: u32, n: u8) -> i32 {
+    if n == 0 {
+        return 0;
+    }
+    let vt = 1i32 << (n - 1);
+    let v = v as i32;
+    if v < vt {
+        v - (1 << n) + 1
+    } else {
+        v
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("truncated LJPEG marker segment"))
+}
+
+fn read_u8(data: &[u8], pos: usize) -> Result<u8> {
+    data.get(pos)
+        .copied()
+        .ok_or_else(|| anyhow!("truncated LJPEG marker segment"))
+}
+
+fn read_slice(data: &[u8], range: std::ops::Range<usize>) -> Result<&[u8]> {
+    data.get(range).ok_or_else(|| anyhow!("truncated LJPEG marker segment"))
+}
+
+/// Decode a single-frame LJPEG stream (as embedded in DNG/NEF/CR2 sensor tiles).
+pub fn decode(data: &[u8]) -> Result<LjpegImage> {
+    if data.get(0..2) != Some(&[0xFF, 0xD8]) {
+        bail!("not a JPEG stream (missing SOI)");
+    }
+
+    let mut pos = 2;
+    let mut huffman_tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut precision = 0u8;
+    let mut width = 0u16;
+    let mut height = 0u16;
+    let mut component_ids = Vec::new();
+    let mut restart_interval = 0u16;
+    let mut predictor_selection = 1u8;
+    let mut scan_table_selectors = Vec::new();
+
+    loop {
+        if data.get(pos) != Some(&0xFF) {
+            bail!("expected marker at offset {pos}");
+        }
+        let marker = read_u8(data, pos + 1)?;
+        pos += 2;
+
+        match marker {
+            0xC3 => {
+                // SOF3: lossless, Huffman coding.
+                let seg_len = read_u16(data, pos)?;
+                precision = read_u8(data, pos + 2)?;
+                height = read_u16(data, pos + 3)?;
+                width = read_u16(data, pos + 5)?;
+                let n = read_u8(data, pos + 7)? as usize;
+                component_ids.clear();
+                for i in 0..n {
+                    component_ids.push(read_u8(data, pos + 8 + i * 3)?);
+                }
+                pos += seg_len as usize;
+            }
+            0xC4 => {
+                // DHT, possibly several tables back-to-back.
+                let len = read_u16(data, pos)? as usize;
+                let end = pos + len;
+                let mut p = pos + 2;
+                while p < end {
+                    let table_id = (read_u8(data, p)? & 0x0F) as usize;
+                    if table_id >= huffman_tables.len() {
+                        bail!("LJPEG Huffman table id {table_id} out of range");
+                    }
+                    let mut bits = [0u8; 16];
+                    bits.copy_from_slice(read_slice(data, p + 1..p + 17)?);
+                    let total: usize = bits.iter().map(|&b| b as usize).sum();
+                    let huffval = read_slice(data, p + 17..p + 17 + total)?;
+                    huffman_tables[table_id] = Some(HuffmanTable::build(&bits, huffval));
+                    p += 17 + total;
+                }
+                pos += len;
+            }
+            0xDD => {
+                let seg_len = read_u16(data, pos)?;
+                restart_interval = read_u16(data, pos + 2)?;
+                pos += seg_len as usize;
+            }
+            0xDA => {
+                // SOS: entropy-coded data follows the header.
+                let len = read_u16(data, pos)? as usize;
+                let n = read_u8(data, pos + 2)? as usize;
+                scan_table_selectors.clear();
+                for i in 0..n {
+                    let selector = read_u8(data, pos + 3 + i * 2 + 1)?;
+                    scan_table_selectors.push((selector >> 4) as usize);
+                }
+                predictor_selection = read_u8(data, pos + 3 + n * 2)?;
+                pos += len;
+                return decode_scan(
+                    read_slice(data, pos..data.len())?,
+                    width,
+                    height,
+                    component_ids.len(),
+                    precision,
+                    predictor_selection,
+                    &huffman_tables,
+                    &scan_table_selectors,
+                    restart_interval,
+                );
+            }
+            0xD8 | 0x01 => {}
+            0xD9 => bail!("LJPEG stream ended before SOS"),
+            _ => {
+                let len = read_u16(data, pos)? as usize;
+                pos += len;
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode_scan(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    components: usize,
+    precision: u8,
+    predictor_selection: u8,
+    huffman_tables: &[Option<HuffmanTable>; 4],
+    table_selectors: &[usize],
+    restart_interval: u16,
+) -> Result<LjpegImage> {
+    if !(1..=16).contains(&precision) {
+        bail!("invalid LJPEG sample precision {precision}");
+    }
+    if components == 0 {
+        bail!("LJPEG frame declares zero components");
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    // `width`/`height`/`components` come straight from attacker-controlled SOF3/SOS
+    // bytes; a crafted strip could otherwise request a multi-terabyte allocation.
+    // Every sample needs at least one bit of entropy-coded data, so the sample count
+    // can't exceed the scan data's bit count.
+    let total_samples = width
+        .checked_mul(height)
+        .and_then(|v| v.checked_mul(components))
+        .ok_or_else(|| anyhow!("LJPEG frame dimensions overflow"))?;
+    if total_samples > data.len().saturating_mul(8) {
+        bail!(
+            "LJPEG frame claims {total_samples} samples, more than the {} bytes of scan data can encode",
+            data.len()
+        );
+    }
+    let mut samples = vec![0u16; total_samples];
+    let default_value: i32 = 1 << (precision - 1);
+
+    let mut reader = BitReader::new(data);
+    let mut since_restart = 0u16;
+
+    for row in 0..height {
+        for col in 0..width {
+            // A restart marker resets prediction as if we were back at the first
+            // sample of each component, regardless of row/column.
+            let just_restarted = restart_interval != 0 && since_restart == restart_interval;
+            if just_restarted {
+                reader.skip_restart_marker();
+                since_restart = 0;
+            }
+            let is_first_sample = row == 0 && col == 0;
+
+            for (c, &table_idx) in table_selectors.iter().enumerate().take(components) {
+                let table = huffman_tables
+                    .get(table_idx)
+                    .and_then(Option::as_ref)
+                    .ok_or_else(|| anyhow!("LJPEG scan references undefined Huffman table {table_idx}"))?;
+                let ssss = reader.decode_huffman(table)?;
+                if ssss > 16 {
+                    bail!("LJPEG difference magnitude {ssss} out of range");
+                }
+                let diff = if ssss == 0 {
+                    0
+                } else {
+                    extend(reader.receive(ssss)?, ssss)
+                };
+
+                let predicted = if is_first_sample || just_restarted {
+                    default_value
+                } else {
+                    let left = (col > 0).then(|| samples[(row * width + col - 1) * components + c] as i32);
+                    let above = (row > 0).then(|| samples[((row - 1) * width + col) * components + c] as i32);
+                    let above_left = (row > 0 && col > 0)
+                        .then(|| samples[((row - 1) * width + col - 1) * components + c] as i32);
+
+                    match (left, above) {
+                        (None, None) => default_value,
+                        (Some(l), None) => l,
+                        (None, Some(a)) => a,
+                        (Some(l), Some(a)) => predict(predictor_selection, l, a, above_left.unwrap_or(a)),
+                    }
+                };
+
+                let value = (predicted + diff).clamp(0, (1 << precision) - 1) as u16;
+                samples[(row * width + col) * components + c] = value;
+            }
+
+            since_restart += 1;
+        }
+    }
+
+    Ok(LjpegImage {
+        width: width as u16,
+        height: height as u16,
+        components,
+        precision,
+        samples,
+    })
+}
+
+/// Apply the SOS predictor selection value (Ss, JPEG spec Table H.1) to reconstructed
+/// left (`ra`), above (`rb`) and above-left (`rc`) neighbours.
+fn predict(selection: u8, ra: i32, rb: i32, rc: i32) -> i32 {
+    match selection {
+        1 => ra,
+        2 => rb,
+        3 => rc,
+        4 => ra + rb - rc,
+        5 => ra + (rb - rc) / 2,
+        6 => rb + (ra - rc) / 2,
+        7 => (ra + rb) / 2,
+        _ => ra,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal single-component, 2x1, 8-bit LJPEG stream: one SOF3 frame, one DHT
+    /// table whose only code (`0`) decodes to symbol 0 (a zero difference), one SOS
+    /// header selecting predictor 1 (left), and a single all-zero entropy byte. Every
+    /// sample therefore decodes to the default value (128) with a zero difference.
+    const MINIMAL_STREAM: &[u8] = &[
+        0xFF, 0xD8, // SOI
+        0xFF, 0xC3, 0x00, 0x0B, 0x08, 0x00, 0x01, 0x00, 0x02, 0x01, 0x01, 0x11, 0x00, // SOF3
+        0xFF, 0xC4, 0x00, 0x14, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // DHT
+        0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x01, 0x00, 0x00, // SOS
+        0x00, // entropy data
+    ];
+
+    #[test]
+    fn decodes_minimal_stream() {
+        let image = decode(MINIMAL_STREAM).unwrap();
+        assert_eq!(image.width, 2);
+        assert_eq!(image.height, 1);
+        assert_eq!(image.components, 1);
+        assert_eq!(image.precision, 8);
+        assert_eq!(image.samples, vec![128, 128]);
+    }
+
+    #[test]
+    fn truncated_stream_errors_instead_of_panicking() {
+        // Cut the stream off partway through the SOF3 segment.
+        let truncated = &MINIMAL_STREAM[..10];
+        assert!(decode(truncated).is_err());
+    }
+
+    #[test]
+    fn truncated_before_sos_entropy_data_errors_instead_of_panicking() {
+        // Keep every marker segment but drop the entropy-coded data entirely.
+        let truncated = &MINIMAL_STREAM[..MINIMAL_STREAM.len() - 1];
+        // No entropy bytes left to read: the frame's 2 samples need more bits than
+        // the empty scan data holds, so decode rejects it with an error.
+        assert!(decode(truncated).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Identify RAW files by content signature instead of trusting their extension.
+
+use crate::exif;
+
+/// How many leading bytes of a file we need to read to sniff it. Checking for a
+/// `Make`/`Model` pair in IFD0 is the furthest into the file of the ones we check —
+/// far enough to cover where camera manufacturers place IFD0 and its inline/nearby
+/// tag values, but still a small, bounded read per file.
+pub const SNIFF_LEN: usize = 4096;
+
+/// RAW formats we can recognise by signature, regardless of file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    /// TIFF-based RAWs other than CR2 (ARW, DNG, NEF, NRW, PEF, ...): an `II*\0`/`MM\0*`
+    /// preamble with a `Make`/`Model` pair in IFD0.
+    Tiff,
+    /// Canon CR2 specifically: TIFF preamble plus a "CR\x02\0" signature at offset 8.
+    Cr2,
+    /// Fujifilm RAF.
+    Raf,
+    /// Sigma X3F.
+    X3f,
+}
+
+/// Sniff `data` (the first [`SNIFF_LEN`] bytes of a file is enough) and classify it
+/// as a known RAW format, if any.
+pub fn classify(data: &[u8]) -> Option<RawFormat> {
+    let tiff_preamble = matches!(data.get(0..4), Some(b"II*\0") | Some(b"MM\0*"));
+
+    if tiff_preamble && data.get(8..12) == Some(b"CR\x02\0") {
+        return Some(RawFormat::Cr2);
+    }
+    // An ordinary (non-RAW) TIFF shares this same preamble, so also require a
+    // known make/model in IFD0 before calling it a RAW file.
+    if tiff_preamble && exif::ifd0_has_make_and_model(data) {
+        return Some(RawFormat::Tiff);
+    }
+    if data.get(0..15) == Some(b"FUJIFILMCCD-RAW") {
+        return Some(RawFormat::Raf);
+    }
+    if data.get(0..4) == Some(b"FOVb") {
+        return Some(RawFormat::X3f);
+    }
+
+    None
+}
+
+/// Does `data` look like a RAW file we know how to handle?
+pub fn is_raw_file(data: &[u8]) -> bool {
+    classify(data).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Build a minimal little-endian TIFF stream; when `with_make_model` is set,
+    /// IFD0 carries non-empty `Make`/`Model` ASCII tags, otherwise IFD0 is empty.
+    fn build_tiff(with_make_model: bool) -> Vec<u8> {
+        let mut buf = vec![0u8; 64];
+        buf[0..2].copy_from_slice(b"II");
+        put_u16(&mut buf, 2, 42);
+        put_u32(&mut buf, 4, 8);
+
+        if with_make_model {
+            put_u16(&mut buf, 8, 2);
+            put_u16(&mut buf, 10, 0x010F); // Make
+            put_u16(&mut buf, 12, 2); // ASCII
+            put_u32(&mut buf, 14, 6);
+            put_u32(&mut buf, 18, 38);
+            put_u16(&mut buf, 22, 0x0110); // Model
+            put_u16(&mut buf, 24, 2); // ASCII
+            put_u32(&mut buf, 26, 7);
+            put_u32(&mut buf, 30, 44);
+            put_u32(&mut buf, 34, 0);
+            buf[38..44].copy_from_slice(b"Canon\0");
+            buf[44..51].copy_from_slice(b"EOS R5\0");
+        } else {
+            put_u16(&mut buf, 8, 0);
+        }
+
+        buf
+    }
+
+    #[test]
+    fn classifies_tiff_with_make_model_as_raw() {
+        let data = build_tiff(true);
+        assert_eq!(classify(&data), Some(RawFormat::Tiff));
+    }
+
+    #[test]
+    fn does_not_classify_ordinary_tiff_as_raw() {
+        let data = build_tiff(false);
+        assert_eq!(classify(&data), None);
+    }
+
+    #[test]
+    fn classifies_cr2_signature_regardless_of_make_model() {
+        let mut data = build_tiff(false);
+        data[8..12].copy_from_slice(b"CR\x02\0");
+        assert_eq!(classify(&data), Some(RawFormat::Cr2));
+    }
+
+    #[test]
+    fn classifies_raf_and_x3f_by_literal_prefix() {
+        let mut raf = vec![0u8; SNIFF_LEN];
+        raf[0..15].copy_from_slice(b"FUJIFILMCCD-RAW");
+        assert_eq!(classify(&raf), Some(RawFormat::Raf));
+
+        let mut x3f = vec![0u8; SNIFF_LEN];
+        x3f[0..4].copy_from_slice(b"FOVb");
+        assert_eq!(classify(&x3f), Some(RawFormat::X3f));
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use clap::Parser;
-use jpgfromraw::{extract_directory, ExtractionConfig};
+use jpgfromraw::{extract_directory, ExtractionConfig, LayoutTemplate, OutputFormat, PreviewSelection};
 use std::ffi::OsString;
 use std::path::PathBuf;
 
@@ -24,6 +24,40 @@ struct Args {
     /// rwl, sr2, srf, srw, x3f
     #[arg(short, long)]
     extension: Option<OsString>,
+
+    /// Rotate/flip extracted previews upright according to EXIF/Makernote orientation
+    #[arg(long)]
+    auto_orient: bool,
+
+    /// Identify RAW files by content signature instead of trusting the file extension
+    #[arg(long)]
+    magic_detection: bool,
+
+    /// Downscale extracted previews so their longest edge is at most this many pixels
+    #[arg(long)]
+    resize: Option<u32>,
+
+    /// Re-encode extracted previews to this format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Jpeg)]
+    format: OutputFormat,
+
+    /// Quality (1-100) to use when re-encoding as JPEG. PNG/WebP output is always
+    /// encoded lossless; with --auto-orient, a rotated preview is first re-encoded as
+    /// JPEG at this quality before any PNG/WebP conversion.
+    #[arg(long, default_value_t = 90)]
+    quality: u8,
+
+    /// Organise output into subdirectories, e.g. "{year}/{month}/{camera_model}"
+    #[arg(long)]
+    layout: Option<String>,
+
+    /// Decode the sensor data and render a JPEG when a RAW file has no embedded preview
+    #[arg(long)]
+    decode_fallback: bool,
+
+    /// Which embedded preview(s) to extract when a RAW file contains more than one
+    #[arg(long, value_enum, default_value_t = PreviewSelection::Largest)]
+    preview_selection: PreviewSelection,
 }
 
 #[tokio::main]
@@ -32,7 +66,15 @@ async fn main() -> Result<()> {
 
     let config = ExtractionConfig::new(args.input_dir, args.output_dir)
         .with_transfers(args.transfers)
-        .with_extension(args.extension);
+        .with_extension(args.extension)
+        .with_auto_orient(args.auto_orient)
+        .with_magic_detection(args.magic_detection)
+        .with_resize(args.resize)
+        .with_format(args.format)
+        .with_quality(args.quality)
+        .with_layout(args.layout.map(LayoutTemplate::new))
+        .with_decode_fallback(args.decode_fallback)
+        .with_preview_selection(args.preview_selection);
 
     extract_directory(config).await
 }
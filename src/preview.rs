@@ -0,0 +1,95 @@
+//! Enumerate and select among the JPEG previews embedded in a RAW file.
+//!
+//! RAW files frequently embed more than one JPEG (a small thumbnail alongside a
+//! near-full-resolution preview); we find every candidate and let
+//! [`PreviewSelection`] decide which to keep.
+
+use crate::exif;
+
+const SOI: [u8; 2] = [0xFF, 0xD8];
+const EOI: [u8; 2] = [0xFF, 0xD9];
+
+/// Which embedded preview(s) to extract when a RAW file contains more than one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PreviewSelection {
+    /// The highest-resolution preview (by byte size, a reliable proxy for pixel
+    /// count across JPEGs from the same body). The default.
+    Largest,
+    /// The smallest preview, typically an embedded thumbnail.
+    Smallest,
+    /// Every preview found, each written with a `_<size>` suffix.
+    All,
+}
+
+impl std::fmt::Display for PreviewSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Largest => "largest",
+            Self::Smallest => "smallest",
+            Self::All => "all",
+        })
+    }
+}
+
+/// Find every embedded JPEG by scanning for SOI/EOI spans, then cross-check
+/// against the RAW file's `JpegIFOffset`/`JpegIFByteCount` IFD pointers: any
+/// IFD-pointer span that doesn't overlap a span we already found by scanning is
+/// added too, so a preview the scan did not cover is still found. A scanned span
+/// ends at the first EOI after its SOI, so a preview whose bytes contain a stray
+/// EOI is cut short there, and its overlapping IFD-pointer span is not added.
+fn find_all_spans(data: &[u8]) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut pos = 0;
+
+    while pos + 2 <= data.len() {
+        let Some(start_rel) = data[pos..].windows(2).position(|w| w == SOI) else {
+            break;
+        };
+        let start = pos + start_rel;
+        let Some(end_rel) = data[start..].windows(2).position(|w| w == EOI) else {
+            break;
+        };
+        let end = start + end_rel + 2;
+        spans.push((start, end));
+        pos = end;
+    }
+
+    if let Ok(ifd_spans) = exif::jpeg_ifd_spans(data) {
+        for (offset, len) in ifd_spans {
+            let end = offset + len;
+            if end <= data.len() && !spans.iter().any(|&(s, e)| ranges_overlap(s, e, offset, end)) {
+                spans.push((offset, end));
+            }
+        }
+    }
+
+    spans
+}
+
+/// Whether `[a_start, a_end)` and `[b_start, b_end)` overlap at all. An `JpegIFOffset`
+/// pointer is only a *duplicate* of a scanned SOI/EOI span if the two describe
+/// overlapping bytes — exact start-offset equality is too strict and lets the same
+/// preview through twice when the IFD's recorded offset is a few bytes off from
+/// where we found its SOI marker.
+fn ranges_overlap(a_start: usize, a_end: usize, b_start: usize, b_end: usize) -> bool {
+    a_start < b_end && b_start < a_end
+}
+
+/// Find every embedded JPEG preview in `data`, largest first.
+pub fn find_all_previews(data: &[u8]) -> Vec<&[u8]> {
+    let mut previews: Vec<&[u8]> = find_all_spans(data)
+        .into_iter()
+        .filter_map(|(start, end)| data.get(start..end))
+        .collect();
+    previews.sort_by_key(|p| std::cmp::Reverse(p.len()));
+    previews
+}
+
+/// Apply `selection` to the (largest-first) list of previews found in a RAW file.
+pub fn select<'a>(previews: &[&'a [u8]], selection: PreviewSelection) -> Vec<&'a [u8]> {
+    match selection {
+        PreviewSelection::Largest => previews.first().copied().into_iter().collect(),
+        PreviewSelection::Smallest => previews.last().copied().into_iter().collect(),
+        PreviewSelection::All => previews.to_vec(),
+    }
+}
@@ -0,0 +1,104 @@
+//! Fallback for RAW files with no usable embedded JPEG preview: decode the sensor
+//! data itself and render it as a JPEG.
+//!
+//! This only handles the common layout of a single LJPEG-compressed strip/tile
+//! covering the whole frame with a standard RGGB Bayer CFA, which is what DNG, NEF
+//! and CR2 most often use for full-resolution sensor data. Frames split across
+//! multiple strips or tiles aren't decoded: [`exif::find_largest_strip`] only
+//! considers single-strip IFDs, so callers find no strip to hand over. For a
+//! non-RGGB CFA layout or a multi-component LJPEG stream, [`decode_fallback`]
+//! returns an error so callers can warn and move on rather than silently producing
+//! a wrong image.
+
+use crate::exif;
+use crate::ljpeg;
+use anyhow::{bail, Context, Result};
+use image::{ImageBuffer, ImageFormat, Rgb};
+use std::io::Cursor;
+
+/// Decode a single-strip LJPEG-compressed Bayer CFA and render it as a JPEG.
+///
+/// `raw_data` is the whole RAW container, used only to check the `CFAPattern` tag;
+/// `strip` is the raw LJPEG stream (the strip/tile payload, not the whole RAW file) —
+/// callers are responsible for locating it via the RAW container's IFD strip
+/// offsets/counts.
+pub fn decode_fallback(raw_data: &[u8], strip: &[u8]) -> Result<Vec<u8>> {
+    if !exif::is_rggb_cfa(raw_data).context("reading CFA pattern")? {
+        bail!("unsupported non-RGGB Bayer CFA layout for RAW decode fallback");
+    }
+
+    let decoded = ljpeg::decode(strip).context("decoding LJPEG sensor strip")?;
+
+    if decoded.components != 1 {
+        bail!(
+            "unsupported LJPEG layout for RAW decode fallback: {} components, expected 1 (single CFA plane)",
+            decoded.components
+        );
+    }
+
+    let rgb = demosaic_rggb(&decoded.samples, decoded.width, decoded.height, decoded.precision);
+
+    let mut out = Cursor::new(Vec::new());
+    rgb.write_to(&mut out, ImageFormat::Jpeg)?;
+    Ok(out.into_inner())
+}
+
+/// Bilinear-interpolate a standard RGGB Bayer CFA plane into an 8-bit RGB image.
+///
+/// Pixel `(x, y)` is: R when `y` even, `x` even; G when `y` even, `x` odd, or `y`
+/// odd, `x` even; B when `y` odd, `x` odd.
+fn demosaic_rggb(samples: &[u16], width: u16, height: u16, precision: u8) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (width, height) = (width as u32, height as u32);
+    let shift = precision.saturating_sub(8);
+    let at = |x: i64, y: i64| -> u16 {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        samples[(y * width + x) as usize]
+    };
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let is_even_row = y % 2 == 0;
+        let is_even_col = x % 2 == 0;
+
+        let (r, g, b) = if is_even_row && is_even_col {
+            // On a red pixel.
+            let r = at(x, y);
+            let g = avg4(at(x - 1, y), at(x + 1, y), at(x, y - 1), at(x, y + 1));
+            let b = avg4(at(x - 1, y - 1), at(x + 1, y - 1), at(x - 1, y + 1), at(x + 1, y + 1));
+            (r, g, b)
+        } else if !is_even_row && !is_even_col {
+            // On a blue pixel.
+            let b = at(x, y);
+            let g = avg4(at(x - 1, y), at(x + 1, y), at(x, y - 1), at(x, y + 1));
+            let r = avg4(at(x - 1, y - 1), at(x + 1, y - 1), at(x - 1, y + 1), at(x + 1, y + 1));
+            (r, g, b)
+        } else if is_even_row {
+            // On a green pixel in a red row: red is left/right, blue is up/down.
+            let g = at(x, y);
+            let r = avg2(at(x - 1, y), at(x + 1, y));
+            let b = avg2(at(x, y - 1), at(x, y + 1));
+            (r, g, b)
+        } else {
+            // On a green pixel in a blue row: blue is left/right, red is up/down.
+            let g = at(x, y);
+            let b = avg2(at(x - 1, y), at(x + 1, y));
+            let r = avg2(at(x, y - 1), at(x, y + 1));
+            (r, g, b)
+        };
+
+        Rgb([to_u8(r, shift), to_u8(g, shift), to_u8(b, shift)])
+    })
+}
+
+fn avg2(a: u16, b: u16) -> u16 {
+    ((a as u32 + b as u32) / 2) as u16
+}
+
+fn avg4(a: u16, b: u16, c: u16, d: u16) -> u16 {
+    ((a as u32 + b as u32 + c as u32 + d as u32) / 4) as u16
+}
+
+fn to_u8(sample: u16, shift: u8) -> u8 {
+    (sample >> shift).min(u8::MAX as u16) as u8
+}